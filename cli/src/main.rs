@@ -1,24 +1,7 @@
 use anyhow::Result;
+use aws_sdk_s3::{Client, Endpoint};
 use clap::{Parser, Subcommand};
 
-struct Replicator {
-    inner: bottomless::replicator::Replicator,
-}
-
-impl std::ops::Deref for Replicator {
-    type Target = bottomless::replicator::Replicator;
-
-    fn deref(&self) -> &Self::Target {
-        &self.inner
-    }
-}
-
-impl std::ops::DerefMut for Replicator {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
-    }
-}
-
 fn uuid_to_datetime(uuid: &uuid::Uuid) -> chrono::NaiveDateTime {
     let (seconds, nanos) = uuid
         .get_timestamp()
@@ -29,201 +12,200 @@ fn uuid_to_datetime(uuid: &uuid::Uuid) -> chrono::NaiveDateTime {
         .unwrap_or(chrono::NaiveDateTime::MIN)
 }
 
-impl Replicator {
-    pub async fn new() -> Result<Self> {
-        Ok(Self {
-            inner: bottomless::replicator::Replicator::new().await?,
-        })
+/// Autodetects the database a bucket holds by finding the first key that embeds a
+/// generation uuid (`<db_name>-<generation>-...`) and taking whatever precedes it.
+async fn detect_db(client: &Client, bucket: &str) -> Option<String> {
+    let response = client.list_objects().bucket(bucket).send().await.ok()?;
+    for key in response.contents()?.iter().filter_map(|obj| obj.key()) {
+        let bytes = key.as_bytes();
+        for (i, b) in bytes.iter().enumerate() {
+            if *b == b'-' && key.len() >= i + 1 + 36 && uuid::Uuid::try_parse(&key[i + 1..i + 1 + 36]).is_ok()
+            {
+                return Some(key[..i].to_owned());
+            }
+        }
+    }
+    None
+}
+
+/// Lightweight bucket-introspection/admin operations for the CLI: listing, removing, and
+/// validating generations. Unlike [`bottomless::s3::Replicator`], which is shaped around
+/// driving sqlite's hot write/restore path, this just wraps a plain S3 client against the
+/// same flat key scheme, the same way `quota::QuotaEnforcer`/`retention::RetentionWorker`
+/// each keep their own lightweight client instead of sharing the replicator's.
+struct Admin {
+    client: Client,
+    bucket: String,
+    db_name: String,
+}
+
+impl Admin {
+    fn generation_prefix(&self, generation: uuid::Uuid) -> String {
+        format!("{}-{}-", self.db_name, generation)
+    }
+
+    /// Lists every object key under `prefix`, looping on `is_truncated`/`next_marker` so
+    /// results beyond a single page are not silently dropped.
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self.client.list_objects().bucket(&self.bucket).prefix(prefix);
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            if let Some(objs) = response.contents() {
+                for obj in objs {
+                    if let Some(key) = obj.key() {
+                        keys.push(key.to_owned());
+                    }
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            next_marker = response
+                .next_marker()
+                .map(|s| s.to_owned())
+                .or_else(|| keys.last().cloned());
+            if next_marker.is_none() {
+                break;
+            }
+        }
+        Ok(keys)
     }
 
-    async fn print_snapshot_summary(&self, generation: &uuid::Uuid) -> Result<()> {
+    /// Every distinct generation uuid that has at least one key under this database's
+    /// flat prefix, sorted oldest-first (UUIDv7's time-ordered layout sorts ascending by
+    /// creation time).
+    async fn all_generations(&self) -> Result<Vec<uuid::Uuid>> {
+        let mut generations = std::collections::BTreeSet::new();
+        for key in self.list_keys(&format!("{}-", self.db_name)).await? {
+            if let Some(generation) = bottomless::s3::generation_from_key(&key, &self.db_name) {
+                generations.insert(generation);
+            }
+        }
+        Ok(generations.into_iter().collect())
+    }
+
+    async fn print_generation_summary(&self, generation: uuid::Uuid) -> Result<()> {
+        let snapshot_key = format!("{}-{}-snapshot", self.db_name, generation);
         match self
             .client
             .get_object_attributes()
             .bucket(&self.bucket)
-            .key(format!("{}-{}/db.gz", self.db_name, generation))
+            .key(&snapshot_key)
             .object_attributes(aws_sdk_s3::model::ObjectAttributes::ObjectSize)
             .send()
             .await
         {
-            Ok(attrs) => {
-                println!("\tmain database snapshot:");
-                println!("\t\tobject size:   {}", attrs.object_size());
-                println!(
-                    "\t\tlast modified: {}",
-                    attrs
-                        .last_modified()
-                        .map(|s| s
-                            .fmt(aws_smithy_types::date_time::Format::DateTime)
-                            .unwrap_or_else(|e| e.to_string()))
-                        .as_deref()
-                        .unwrap_or("never")
-                );
-            }
+            Ok(attrs) => println!("\tsnapshot:  {} bytes", attrs.object_size()),
             Err(aws_sdk_s3::types::SdkError::ServiceError(err)) if err.err().is_no_such_key() => {
-                println!("\tno main database snapshot file found")
+                println!("\tsnapshot:  none")
             }
-            Err(e) => println!("\tfailed to fetch main database snapshot info: {}", e),
-        };
+            Err(e) => println!("\tsnapshot:  failed to fetch attributes: {}", e),
+        }
+        let segments = self
+            .list_keys(&format!("{}-segment-", self.generation_prefix(generation)))
+            .await?;
+        println!("\tsegments:  {}", segments.len());
         Ok(())
     }
 
-    pub async fn list_generations(
+    async fn list_generations(
         &self,
         limit: Option<u64>,
         older_than: Option<chrono::NaiveDate>,
         newer_than: Option<chrono::NaiveDate>,
         verbose: bool,
     ) -> Result<()> {
-        let mut next_marker = None;
-        let mut limit = limit.unwrap_or(u64::MAX);
-        loop {
-            let mut list_request = self
-                .client
-                .list_objects()
-                .bucket(&self.bucket)
-                .set_delimiter(Some("/".to_string()))
-                .prefix(&self.db_name);
+        let mut generations = self.all_generations().await?;
+        generations.reverse(); // newest first
+        if generations.is_empty() {
+            println!("No generations found");
+            return Ok(());
+        }
 
-            if let Some(marker) = next_marker {
-                list_request = list_request.marker(marker)
+        let limit = limit.unwrap_or(u64::MAX);
+        let mut shown = 0u64;
+        for generation in generations {
+            let datetime = uuid_to_datetime(&generation);
+            if datetime.date() < newer_than.unwrap_or(chrono::NaiveDate::MIN) {
+                continue;
             }
-
-            let response = list_request.send().await?;
-            let prefixes = match response.common_prefixes() {
-                Some(prefixes) => prefixes,
-                None => {
-                    println!("No generations found");
-                    return Ok(());
-                }
-            };
-
-            for prefix in prefixes {
-                if let Some(prefix) = &prefix.prefix {
-                    let prefix = &prefix[self.db_name.len() + 1..prefix.len() - 1];
-                    let uuid = uuid::Uuid::try_parse(prefix)?;
-                    let datetime = uuid_to_datetime(&uuid);
-                    if datetime.date() < newer_than.unwrap_or(chrono::NaiveDate::MIN) {
-                        continue;
-                    }
-                    if datetime.date() > older_than.unwrap_or(chrono::NaiveDate::MAX) {
-                        continue;
-                    }
-                    println!("{}", uuid);
-                    if verbose {
-                        let counter = self.get_remote_change_counter(&uuid).await?;
-                        let consistent_frame = self.get_last_consistent_frame(&uuid).await?;
-                        println!("\tcreated at (UTC):     {}", datetime);
-                        println!("\tchange counter:       {:?}", counter);
-                        println!("\tconsistent WAL frame: {}", consistent_frame);
-                        self.print_snapshot_summary(&uuid).await?;
-                        println!()
-                    }
-                }
-                limit -= 1;
-                if limit == 0 {
-                    return Ok(());
-                }
+            if datetime.date() > older_than.unwrap_or(chrono::NaiveDate::MAX) {
+                continue;
             }
-
-            next_marker = response.next_marker().map(|s| s.to_owned());
-            if next_marker.is_none() {
+            println!("{}", generation);
+            if verbose {
+                println!("\tcreated at (UTC): {}", datetime);
+                self.print_generation_summary(generation).await?;
+                println!();
+            }
+            shown += 1;
+            if shown >= limit {
                 return Ok(());
             }
         }
+        Ok(())
     }
 
-    pub async fn remove(&self, generation: uuid::Uuid, verbose: bool) -> Result<()> {
-        let mut next_marker = None;
-        loop {
-            let mut list_request = self
-                .client
-                .list_objects()
-                .bucket(&self.bucket)
-                .prefix(format!("{}-{}/", &self.db_name, generation));
-
-            if let Some(marker) = next_marker {
-                list_request = list_request.marker(marker)
-            }
-
-            let response = list_request.send().await?;
-            let objs = match response.contents() {
-                Some(prefixes) => prefixes,
-                None => {
-                    if verbose {
-                        println!("No objects found")
-                    }
-                    return Ok(());
-                }
-            };
-
-            for obj in objs {
-                if let Some(key) = obj.key() {
-                    if verbose {
-                        println!("Removing {}", key)
-                    }
-                    self.client
-                        .delete_object()
-                        .bucket(&self.bucket)
-                        .key(key)
-                        .send()
-                        .await?;
-                }
-            }
+    async fn list_generation(&self, generation: uuid::Uuid) -> Result<()> {
+        let exists = !self
+            .client
+            .list_objects()
+            .bucket(&self.bucket)
+            .prefix(self.generation_prefix(generation))
+            .max_keys(1)
+            .send()
+            .await?
+            .contents()
+            .unwrap_or_default()
+            .is_empty();
+        if !exists {
+            return Err(anyhow::anyhow!(
+                "Generation {} not found for {}",
+                generation,
+                &self.db_name
+            ));
+        }
+        println!("Generation {} for {}", generation, self.db_name);
+        println!("\tcreated at: {}", uuid_to_datetime(&generation));
+        self.print_generation_summary(generation).await?;
+        Ok(())
+    }
 
-            next_marker = response.next_marker().map(|s| s.to_owned());
-            if next_marker.is_none() {
-                return Ok(());
+    async fn remove(&self, generation: uuid::Uuid, verbose: bool) -> Result<()> {
+        let keys = self.list_keys(&self.generation_prefix(generation)).await?;
+        if keys.is_empty() && verbose {
+            println!("No objects found");
+        }
+        for key in keys {
+            if verbose {
+                println!("Removing {}", key);
             }
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .send()
+                .await?;
         }
+        Ok(())
     }
 
-    pub async fn remove_many(&self, older_than: chrono::NaiveDate, verbose: bool) -> Result<()> {
-        let mut next_marker = None;
+    async fn remove_many(&self, older_than: chrono::NaiveDate, verbose: bool) -> Result<()> {
         let mut removed_count = 0;
-        loop {
-            let mut list_request = self
-                .client
-                .list_objects()
-                .bucket(&self.bucket)
-                .set_delimiter(Some("/".to_string()))
-                .prefix(&self.db_name);
-
-            if let Some(marker) = next_marker {
-                list_request = list_request.marker(marker)
+        for generation in self.all_generations().await? {
+            if uuid_to_datetime(&generation).date() >= older_than {
+                continue;
             }
-
-            let response = list_request.send().await?;
-            let prefixes = match response.common_prefixes() {
-                Some(prefixes) => prefixes,
-                None => {
-                    if verbose {
-                        println!("No generations found")
-                    }
-                    return Ok(());
-                }
-            };
-
-            for prefix in prefixes {
-                if let Some(prefix) = &prefix.prefix {
-                    let prefix = &prefix[self.db_name.len() + 1..prefix.len() - 1];
-                    let uuid = uuid::Uuid::try_parse(prefix)?;
-                    let datetime = uuid_to_datetime(&uuid);
-                    if datetime.date() >= older_than {
-                        continue;
-                    }
-                    if verbose {
-                        println!("Removing {}", uuid);
-                    }
-                    self.remove(uuid, verbose).await?;
-                    removed_count += 1;
-                }
-            }
-
-            next_marker = response.next_marker().map(|s| s.to_owned());
-            if next_marker.is_none() {
-                break;
+            if verbose {
+                println!("Removing {}", generation);
             }
+            self.remove(generation, verbose).await?;
+            removed_count += 1;
         }
         if verbose {
             println!("Removed {} generations", removed_count);
@@ -231,49 +213,77 @@ impl Replicator {
         Ok(())
     }
 
-    pub async fn list_generation(&self, generation: uuid::Uuid) -> Result<()> {
-        self.client
-            .list_objects()
-            .bucket(&self.bucket)
-            .prefix(format!("{}-{}/", &self.db_name, generation))
-            .max_keys(1)
-            .send()
-            .await?
-            .contents()
-            .ok_or_else(|| {
-                anyhow::anyhow!("Generation {} not found for {}", generation, &self.db_name)
-            })?;
+    /// Validates a generation without doing a full restore: confirms the snapshot (if
+    /// any) is a whole number of pages, and that its segment indices form a contiguous
+    /// run from 0 with no gaps, mirroring the invariant `Replicator::commit`'s sequential
+    /// `next_segment` depends on.
+    async fn verify_generation(&self, generation: uuid::Uuid) -> Result<VerifyReport> {
+        let mut issues = Vec::new();
 
-        let counter = self.get_remote_change_counter(&generation).await?;
-        let consistent_frame = self.get_last_consistent_frame(&generation).await?;
-        println!("Generation {} for {}", generation, self.db_name);
-        println!("\tcreated at:           {}", uuid_to_datetime(&generation));
-        println!("\tchange counter:       {:?}", counter);
-        println!("\tconsistent WAL frame: {}", consistent_frame);
-        self.print_snapshot_summary(&generation).await?;
-        Ok(())
-    }
-
-    async fn detect_db(&self) -> Option<String> {
-        let response = match self
+        let snapshot_key = format!("{}-{}-snapshot", self.db_name, generation);
+        match self
             .client
-            .list_objects()
+            .get_object_attributes()
             .bucket(&self.bucket)
-            .set_delimiter(Some("/".to_string()))
-            .prefix(&self.db_name)
+            .key(&snapshot_key)
+            .object_attributes(aws_sdk_s3::model::ObjectAttributes::ObjectSize)
             .send()
             .await
         {
-            Ok(resp) => resp,
-            Err(_) => return None,
-        };
+            Ok(attrs) => {
+                let size = attrs.object_size();
+                if size % bottomless::s3::Replicator::PAGE_SIZE as i64 != 0 {
+                    issues.push(format!(
+                        "snapshot size {} is not a multiple of the page size",
+                        size
+                    ));
+                }
+            }
+            Err(aws_sdk_s3::types::SdkError::ServiceError(err)) if err.err().is_no_such_key() => {
+                // No rollover has happened for this generation yet; its segments alone
+                // can still restore it, so a missing snapshot isn't itself an issue.
+            }
+            Err(e) => issues.push(format!("failed to fetch snapshot attributes: {}", e)),
+        }
+
+        let segment_prefix = format!("{}-segment-", self.generation_prefix(generation));
+        let mut indices: Vec<u64> = self
+            .list_keys(&segment_prefix)
+            .await?
+            .into_iter()
+            .filter_map(|key| key[segment_prefix.len()..].parse::<u64>().ok())
+            .collect();
+        indices.sort_unstable();
+        for (expected, actual) in (0u64..).zip(indices.iter()) {
+            if expected != *actual {
+                issues.push(format!("missing segment index {}", expected));
+                break;
+            }
+        }
+
+        Ok(VerifyReport {
+            generation,
+            passed: issues.is_empty(),
+            issues,
+        })
+    }
+}
+
+struct VerifyReport {
+    generation: uuid::Uuid,
+    passed: bool,
+    issues: Vec<String>,
+}
 
-        let prefix = response.common_prefixes()?.first()?.prefix()?;
-        // 38 is the length of the uuid part
-        if let Some('-') = prefix.chars().nth(prefix.len().saturating_sub(38)) {
-            Some(prefix[..prefix.len().saturating_sub(38)].to_owned())
+impl VerifyReport {
+    fn print(&self) {
+        if self.passed {
+            println!("{}: PASS", self.generation);
         } else {
-            None
+            println!("{}: FAIL", self.generation);
+            for issue in &self.issues {
+                println!("\t- {}", issue);
+            }
         }
     }
 }
@@ -290,6 +300,16 @@ struct Cli {
     bucket: Option<String>,
     #[clap(long, short)]
     database: Option<String>,
+    #[clap(
+        long,
+        long_help = "Maximum total bytes stored across all generations before the oldest is evicted.\nFalls back to the LIBSQL_BOTTOMLESS_MAX_BYTES_TOTAL env var."
+    )]
+    max_bytes_total: Option<u64>,
+    #[clap(
+        long,
+        long_help = "Maximum number of generations retained before the oldest is evicted.\nFalls back to the LIBSQL_BOTTOMLESS_MAX_GENERATIONS env var."
+    )]
+    max_generations: Option<u64>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -329,9 +349,17 @@ enum Commands {
         #[clap(
             long,
             short,
+            conflicts_with = "timestamp",
             long_help = "Generation to restore from.\nSkip this parameter to restore from the newest generation."
         )]
         generation: Option<uuid::Uuid>,
+        #[clap(
+            long,
+            short,
+            conflicts_with = "generation",
+            long_help = "Restore to the most recent commit at or before this RFC 3339 timestamp, instead of a full generation boundary."
+        )]
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
     },
     #[clap(about = "Remove given generation from remote storage")]
     Rm {
@@ -346,6 +374,41 @@ enum Commands {
         #[clap(long, short)]
         verbose: bool,
     },
+    #[clap(about = "Run the background retention sweep once, or print its current state")]
+    Retention {
+        #[clap(long, long_help = "Print the persisted retention state and exit")]
+        print_state: bool,
+        #[clap(long, long_help = "Always keep at least this many of the newest generations")]
+        keep_newest: Option<u64>,
+        #[clap(long, long_help = "Expire generations older than this many days")]
+        max_age_days: Option<i64>,
+        #[clap(long, long_help = "Keep the newest generation of each calendar day")]
+        keep_one_per_day: bool,
+        #[clap(long, long_help = "Keep the newest generation of each calendar week")]
+        keep_one_per_week: bool,
+    },
+    #[clap(about = "Print the current storage usage counter for this database")]
+    Usage,
+    #[clap(about = "Re-derive the storage usage counter from actual object sizes")]
+    RepairUsage,
+    #[clap(about = "Validate one or every generation without doing a full restore")]
+    Verify {
+        #[clap(long, short, long_help = "Verify only this generation")]
+        generation: Option<uuid::Uuid>,
+    },
+    #[clap(about = "Validate generations and delete the ones that are unrecoverable")]
+    Repair {
+        #[clap(long, short, long_help = "Repair only this generation")]
+        generation: Option<uuid::Uuid>,
+    },
+    #[clap(about = "Serve Prometheus metrics for the replication path until interrupted")]
+    Metrics {
+        #[clap(
+            long,
+            long_help = "Address to bind the metrics HTTP server to, e.g. 0.0.0.0:9090.\nFalls back to the LIBSQL_BOTTOMLESS_METRICS_ADDR env var."
+        )]
+        addr: Option<std::net::SocketAddr>,
+    },
 }
 
 async fn run() -> Result<()> {
@@ -360,23 +423,42 @@ async fn run() -> Result<()> {
         std::env::set_var("LIBSQL_BOTTOMLESS_BUCKET", bucket)
     }
 
-    let mut client = Replicator::new().await?;
+    if let Some(max_bytes_total) = options.max_bytes_total {
+        std::env::set_var("LIBSQL_BOTTOMLESS_MAX_BYTES_TOTAL", max_bytes_total.to_string())
+    }
+
+    if let Some(max_generations) = options.max_generations {
+        std::env::set_var("LIBSQL_BOTTOMLESS_MAX_GENERATIONS", max_generations.to_string())
+    }
+
+    let endpoint = std::env::var("LIBSQL_BOTTOMLESS_ENDPOINT")
+        .unwrap_or_else(|_| "http://localhost:9000".to_string());
+    let bucket =
+        std::env::var("LIBSQL_BOTTOMLESS_BUCKET").unwrap_or_else(|_| "bottomless".to_string());
+    let client = Client::new(
+        &aws_config::from_env()
+            .endpoint_resolver(Endpoint::immutable(endpoint.parse()?))
+            .load()
+            .await,
+    );
 
     let database = match options.database {
         Some(db) => db,
-        None => {
-            match client.detect_db().await {
-                Some(db) => db,
-                None => {
-                    println!("Could not autodetect the database. Please pass it explicitly with -d option");
-                    return Ok(());
-                }
+        None => match detect_db(&client, &bucket).await {
+            Some(db) => db,
+            None => {
+                println!("Could not autodetect the database. Please pass it explicitly with -d option");
+                return Ok(());
             }
-        }
+        },
     };
     tracing::info!("Database: {}", database);
 
-    client.register_db(database);
+    let admin = Admin {
+        client,
+        bucket,
+        db_name: database.clone(),
+    };
 
     match options.command {
         Commands::Ls {
@@ -386,31 +468,128 @@ async fn run() -> Result<()> {
             newer_than,
             verbose,
         } => match generation {
-            Some(gen) => client.list_generation(gen).await?,
+            Some(gen) => admin.list_generation(gen).await?,
             None => {
-                client
+                admin
                     .list_generations(limit, older_than, newer_than, verbose)
                     .await?
             }
         },
-        Commands::Restore { generation } => {
-            match generation {
-                Some(gen) => client.restore_from(gen).await?,
-                None => client.restore().await?,
-            };
+        Commands::Restore { generation, timestamp } => {
+            let target = timestamp.map(|timestamp| {
+                std::time::UNIX_EPOCH
+                    + std::time::Duration::from_secs(timestamp.timestamp().max(0) as u64)
+            });
+            let database = database.clone();
+            let outcome = tokio::task::spawn_blocking(move || {
+                let replicator = bottomless::s3::Replicator::new(database)?;
+                let replicator = match generation {
+                    Some(generation) => replicator.with_generation(generation),
+                    None => replicator,
+                };
+                let on_progress = |progress: bottomless::s3::RestoreProgress| {
+                    println!("Restored {} pages so far", progress.pages_restored);
+                };
+                match (target, std::env::var_os(bottomless::s3::RESTORE_TIMESTAMP_ENV)) {
+                    (None, None) => replicator.restore(on_progress),
+                    _ => replicator.restore_at(target, on_progress),
+                }
+            })
+            .await??;
+            println!(
+                "Restored {} pages ({} bytes)",
+                outcome.pages.len(),
+                outcome.pages.len() * bottomless::s3::Replicator::PAGE_SIZE
+            );
         }
         Commands::Rm {
             generation,
             older_than,
             verbose,
         } => match (generation, older_than) {
-            (None, Some(older_than)) => client.remove_many(older_than, verbose).await?,
-            (Some(generation), None) => client.remove(generation, verbose).await?,
+            (None, Some(older_than)) => admin.remove_many(older_than, verbose).await?,
+            (Some(generation), None) => admin.remove(generation, verbose).await?,
             (Some(_), Some(_)) => unreachable!(),
             (None, None) => println!(
                 "rm command cannot be run without parameters; see -h or --help for details"
             ),
         },
+        Commands::Retention {
+            print_state,
+            keep_newest,
+            max_age_days,
+            keep_one_per_day,
+            keep_one_per_week,
+        } => {
+            let policy = bottomless::retention::RetentionPolicy {
+                keep_newest,
+                max_age: max_age_days.map(chrono::Duration::days),
+                keep_one_per_day,
+                keep_one_per_week,
+            };
+            let worker = bottomless::retention::RetentionWorker::new(database, policy).await?;
+            if print_state {
+                match worker.state().await? {
+                    Some(state) => println!("{:?}", state),
+                    None => println!("No retention sweep has run yet"),
+                }
+            } else {
+                let state = worker.run_once().await?;
+                println!("{:?}", state);
+            }
+        }
+        Commands::Usage => {
+            let enforcer = bottomless::quota::QuotaEnforcer::new(database).await?;
+            let usage = enforcer.usage().await?;
+            println!("Total bytes:       {}", usage.total_bytes);
+            println!("Generation count:  {}", usage.generation_count);
+        }
+        Commands::RepairUsage => {
+            let enforcer = bottomless::quota::QuotaEnforcer::new(database).await?;
+            let usage = enforcer.repair_usage().await?;
+            println!("Repaired usage counter:");
+            println!("Total bytes:       {}", usage.total_bytes);
+            println!("Generation count:  {}", usage.generation_count);
+        }
+        Commands::Verify { generation } => {
+            let generations = match generation {
+                Some(gen) => vec![gen],
+                None => admin.all_generations().await?,
+            };
+            for generation in generations {
+                admin.verify_generation(generation).await?.print();
+            }
+        }
+        Commands::Repair { generation } => {
+            let generations = match generation {
+                Some(gen) => vec![gen],
+                None => admin.all_generations().await?,
+            };
+            for generation in generations {
+                let report = admin.verify_generation(generation).await?;
+                report.print();
+                if !report.passed {
+                    println!("Deleting unrecoverable generation {}", generation);
+                    admin.remove(generation, false).await?;
+                }
+            }
+        }
+        Commands::Metrics { addr } => {
+            let addr = addr
+                .or_else(|| {
+                    std::env::var(bottomless::metrics::METRICS_ADDR_ENV)
+                        .ok()
+                        .and_then(|s| s.parse().ok())
+                })
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "no metrics address given; pass --addr or set {}",
+                        bottomless::metrics::METRICS_ADDR_ENV
+                    )
+                })?;
+            let metrics = std::sync::Arc::new(bottomless::metrics::Metrics::new());
+            bottomless::metrics::serve(metrics, addr).await?;
+        }
     };
     Ok(())
 }
@@ -0,0 +1,345 @@
+//! Per-database storage quotas, enforced by evicting the oldest generations.
+//!
+//! A running [`UsageCounter`] is kept as `<db>-usage` in the bucket and updated on every
+//! write. Because the counter can drift after a crash between an upload and the counter
+//! update, [`QuotaEnforcer::repair_usage`] re-derives the true totals from object metadata.
+
+use aws_sdk_s3::{Client, Endpoint};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+pub type Result<T> = anyhow::Result<T>;
+
+/// Whether `usage` exceeds either configured limit. Takes the limits explicitly rather
+/// than `&self` since it's a pure function of the configured quota and the current
+/// counter, not of the enforcer's S3 client/bucket.
+fn over_quota(max_bytes: Option<u64>, max_generations: Option<u64>, usage: &UsageCounter) -> bool {
+    max_bytes.map_or(false, |max| usage.total_bytes > max)
+        || max_generations.map_or(false, |max| usage.generation_count > max)
+}
+
+/// Total bytes and generation count recorded for a database, persisted as `<db>-usage`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageCounter {
+    pub total_bytes: u64,
+    pub generation_count: u64,
+}
+
+pub struct QuotaEnforcer {
+    client: Client,
+    bucket: String,
+    db_name: String,
+    /// Maximum total bytes stored across all generations, from `LIBSQL_BOTTOMLESS_MAX_BYTES_TOTAL`.
+    pub max_bytes: Option<u64>,
+    /// Maximum number of generations retained, from `LIBSQL_BOTTOMLESS_MAX_GENERATIONS`.
+    pub max_generations: Option<u64>,
+}
+
+impl QuotaEnforcer {
+    pub async fn new(db_name: impl Into<String>) -> Result<Self> {
+        let endpoint = std::env::var("LIBSQL_BOTTOMLESS_ENDPOINT")
+            .unwrap_or_else(|_| "http://localhost:9000".to_string());
+        let client = Client::new(
+            &aws_config::from_env()
+                .endpoint_resolver(Endpoint::immutable(endpoint.parse()?))
+                .load()
+                .await,
+        );
+        let bucket =
+            std::env::var("LIBSQL_BOTTOMLESS_BUCKET").unwrap_or_else(|_| "bottomless".to_string());
+        let max_bytes = std::env::var("LIBSQL_BOTTOMLESS_MAX_BYTES_TOTAL")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let max_generations = std::env::var("LIBSQL_BOTTOMLESS_MAX_GENERATIONS")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        Ok(Self {
+            client,
+            bucket,
+            db_name: db_name.into(),
+            max_bytes,
+            max_generations,
+        })
+    }
+
+    fn usage_key(&self) -> String {
+        format!("{}-usage", self.db_name)
+    }
+
+    pub async fn usage(&self) -> Result<UsageCounter> {
+        match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.usage_key())
+            .send()
+            .await
+        {
+            Ok(obj) => {
+                let bytes = obj.body.collect().await?.into_bytes();
+                Ok(serde_json::from_slice(&bytes)?)
+            }
+            Err(aws_sdk_s3::types::SdkError::ServiceError(err)) if err.err().is_no_such_key() => {
+                Ok(UsageCounter::default())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_usage(&self, usage: &UsageCounter) -> Result<()> {
+        let body = serde_json::to_vec(usage)?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.usage_key())
+            .body(body.into())
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Records a newly-uploaded generation's size and evicts the oldest generations until
+    /// usage is back within the configured limits. Returns an error if the new generation
+    /// alone exceeds `max_bytes`.
+    pub async fn record_and_enforce(&self, new_generation: uuid::Uuid, bytes_written: u64) -> Result<()> {
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes_written > max_bytes {
+                return Err(anyhow::anyhow!(
+                    "Generation {} alone ({} bytes) exceeds the configured quota of {} bytes",
+                    new_generation,
+                    bytes_written,
+                    max_bytes
+                ));
+            }
+        }
+
+        let mut usage = self.usage().await?;
+        usage.total_bytes += bytes_written;
+        usage.generation_count += 1;
+
+        while over_quota(self.max_bytes, self.max_generations, &usage) {
+            let oldest = match self.oldest_generation().await? {
+                Some(g) => g,
+                None => break,
+            };
+            let freed = self.generation_size(oldest).await?;
+            info!(
+                "Evicting generation {} ({} bytes) to stay within quota",
+                oldest, freed
+            );
+            self.remove_generation(oldest).await?;
+            usage.total_bytes = usage.total_bytes.saturating_sub(freed);
+            usage.generation_count = usage.generation_count.saturating_sub(1);
+        }
+
+        self.save_usage(&usage).await
+    }
+
+    async fn oldest_generation(&self) -> Result<Option<uuid::Uuid>> {
+        // UUIDv7's time-ordered layout means the lowest-sorting generation uuid is also
+        // the oldest, so this doesn't need to separately track/compare timestamps.
+        let mut oldest: Option<uuid::Uuid> = None;
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(format!("{}-", self.db_name));
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            if let Some(objs) = response.contents() {
+                for obj in objs {
+                    if let Some(key) = obj.key() {
+                        if let Some(generation) = crate::s3::generation_from_key(key, &self.db_name) {
+                            oldest = Some(oldest.map_or(generation, |current| current.min(generation)));
+                        }
+                    }
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            next_marker = response.next_marker().map(|s| s.to_owned());
+            if next_marker.is_none() {
+                break;
+            }
+        }
+        Ok(oldest)
+    }
+
+    /// Re-derives the true usage totals by listing every generation's keys and summing
+    /// their object sizes via `get_object_attributes`, then rewrites the persisted
+    /// counter. Use this to recover from drift after a crash.
+    pub async fn repair_usage(&self) -> Result<UsageCounter> {
+        let mut repaired = UsageCounter::default();
+        let mut generations = std::collections::BTreeSet::new();
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(format!("{}-", self.db_name));
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            if let Some(objs) = response.contents() {
+                for obj in objs {
+                    if let Some(key) = obj.key() {
+                        if let Some(generation) = crate::s3::generation_from_key(key, &self.db_name) {
+                            generations.insert(generation);
+                        }
+                    }
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            next_marker = response
+                .next_marker()
+                .map(|s| s.to_owned())
+                .or_else(|| response.contents().and_then(|objs| objs.last()?.key()).map(|s| s.to_owned()));
+            if next_marker.is_none() {
+                break;
+            }
+        }
+
+        for generation in generations {
+            repaired.generation_count += 1;
+            repaired.total_bytes += self.generation_size(generation).await.unwrap_or_else(|e| {
+                warn!("Failed to size generation {}: {}", generation, e);
+                0
+            });
+        }
+
+        self.save_usage(&repaired).await?;
+        Ok(repaired)
+    }
+
+    async fn generation_size(&self, generation: uuid::Uuid) -> Result<u64> {
+        self.prefix_size(&format!("{}-{}-", self.db_name, generation))
+            .await
+    }
+
+    async fn prefix_size(&self, prefix: &str) -> Result<u64> {
+        let mut total = 0u64;
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(prefix);
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            let objs = match response.contents() {
+                Some(objs) => objs,
+                None => break,
+            };
+            for obj in objs {
+                if let Some(key) = obj.key() {
+                    let attrs = self
+                        .client
+                        .get_object_attributes()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .object_attributes(aws_sdk_s3::model::ObjectAttributes::ObjectSize)
+                        .send()
+                        .await?;
+                    total += attrs.object_size().max(0) as u64;
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            next_marker = response
+                .next_marker()
+                .map(|s| s.to_owned())
+                .or_else(|| objs.last().and_then(|obj| obj.key()).map(|s| s.to_owned()));
+            if next_marker.is_none() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    async fn remove_generation(&self, generation: uuid::Uuid) -> Result<()> {
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(format!("{}-{}-", &self.db_name, generation));
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            let objs = match response.contents() {
+                Some(objs) => objs,
+                None => return Ok(()),
+            };
+            for obj in objs {
+                if let Some(key) = obj.key() {
+                    self.client
+                        .delete_object()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .send()
+                        .await?;
+                }
+            }
+            next_marker = response.next_marker().map(|s| s.to_owned());
+            if next_marker.is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn under_both_limits_is_not_over_quota() {
+        let usage = UsageCounter {
+            total_bytes: 10,
+            generation_count: 1,
+        };
+        assert!(!over_quota(Some(100), Some(5), &usage));
+    }
+
+    #[test]
+    fn over_max_bytes_is_over_quota() {
+        let usage = UsageCounter {
+            total_bytes: 200,
+            generation_count: 1,
+        };
+        assert!(over_quota(Some(100), Some(5), &usage));
+    }
+
+    #[test]
+    fn over_max_generations_is_over_quota() {
+        let usage = UsageCounter {
+            total_bytes: 10,
+            generation_count: 6,
+        };
+        assert!(over_quota(Some(100), Some(5), &usage));
+    }
+
+    #[test]
+    fn unset_limits_never_trigger() {
+        let usage = UsageCounter {
+            total_bytes: u64::MAX,
+            generation_count: u64::MAX,
+        };
+        assert!(!over_quota(None, None, &usage));
+    }
+}
@@ -0,0 +1,324 @@
+//! Background retention for replicated generations.
+//!
+//! Unlike `Rm --older-than`, which is a one-shot operator command, [`RetentionWorker`]
+//! runs as a long-lived tokio task that periodically walks every generation of a
+//! database and expires the ones that fall outside a [`RetentionPolicy`]. Progress is
+//! checkpointed to the bucket so a crash mid-sweep resumes instead of restarting.
+
+use aws_sdk_s3::{Client, Endpoint};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+pub type Result<T> = anyhow::Result<T>;
+
+/// How many generations to scan between persisting [`RetentionState::Running`].
+const CHECKPOINT_EVERY: u64 = 200;
+
+/// How often the worker wakes up to run a pass, in steady state.
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24);
+
+/// Rules used to decide whether a generation should be expired.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Always keep at least this many of the newest generations, regardless of age.
+    pub keep_newest: Option<u64>,
+    /// Expire any generation older than this, subject to `keep_newest` and the
+    /// per-day/per-week exceptions below.
+    pub max_age: Option<Duration>,
+    /// Even past `max_age`, keep the newest generation of each calendar day.
+    pub keep_one_per_day: bool,
+    /// Even past `max_age`, keep the newest generation of each calendar week.
+    pub keep_one_per_week: bool,
+}
+
+/// Persisted progress for a retention sweep, stored as `<db>-retention-state` in the bucket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status")]
+pub enum RetentionState {
+    Completed { date: NaiveDate },
+    Running {
+        date: NaiveDate,
+        last_marker: Option<String>,
+        generations_scanned: u64,
+        generations_expired: u64,
+    },
+}
+
+fn uuid_to_datetime(uuid: &uuid::Uuid) -> NaiveDateTime {
+    let (seconds, nanos) = uuid.get_timestamp().map(|ts| ts.to_unix()).unwrap_or((0, 0));
+    NaiveDateTime::from_timestamp_opt(seconds as i64, nanos).unwrap_or(NaiveDateTime::MIN)
+}
+
+/// Decides whether the generation ranked `rank` (0 = newest) and created at `datetime`
+/// survives `policy`, given the calendar days/weeks already kept by a higher-ranked
+/// generation in the same sweep. Takes `policy` explicitly rather than `&self` since it's
+/// a pure function of the policy and the running sweep state, not of the worker's S3
+/// client/bucket.
+fn should_keep(
+    policy: &RetentionPolicy,
+    rank: u64,
+    today: NaiveDate,
+    datetime: NaiveDateTime,
+    kept_days: &mut HashSet<NaiveDate>,
+    kept_weeks: &mut HashSet<i32>,
+) -> bool {
+    if let Some(keep_newest) = policy.keep_newest {
+        if rank < keep_newest {
+            return true;
+        }
+    }
+    let too_old = match policy.max_age {
+        Some(max_age) => today - datetime.date() > max_age,
+        None => false,
+    };
+    if !too_old {
+        return true;
+    }
+    if policy.keep_one_per_day && kept_days.insert(datetime.date()) {
+        return true;
+    }
+    if policy.keep_one_per_week {
+        let week = datetime.iso_week().week() as i32 + datetime.iso_week().year() * 100;
+        if kept_weeks.insert(week) {
+            return true;
+        }
+    }
+    false
+}
+
+pub struct RetentionWorker {
+    client: Client,
+    bucket: String,
+    db_name: String,
+    policy: RetentionPolicy,
+}
+
+impl RetentionWorker {
+    pub async fn new(db_name: impl Into<String>, policy: RetentionPolicy) -> Result<Self> {
+        let endpoint = std::env::var("LIBSQL_BOTTOMLESS_ENDPOINT")
+            .unwrap_or_else(|_| "http://localhost:9000".to_string());
+        let client = Client::new(
+            &aws_config::from_env()
+                .endpoint_resolver(Endpoint::immutable(endpoint.parse()?))
+                .load()
+                .await,
+        );
+        let bucket =
+            std::env::var("LIBSQL_BOTTOMLESS_BUCKET").unwrap_or_else(|_| "bottomless".to_string());
+        Ok(Self {
+            client,
+            bucket,
+            db_name: db_name.into(),
+            policy,
+        })
+    }
+
+    fn state_key(&self) -> String {
+        format!("{}-retention-state", self.db_name)
+    }
+
+    pub async fn state(&self) -> Result<Option<RetentionState>> {
+        match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.state_key())
+            .send()
+            .await
+        {
+            Ok(obj) => {
+                let bytes = obj.body.collect().await?.into_bytes();
+                Ok(Some(serde_json::from_slice(&bytes)?))
+            }
+            Err(aws_sdk_s3::types::SdkError::ServiceError(err)) if err.err().is_no_such_key() => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_state(&self, state: &RetentionState) -> Result<()> {
+        let body = serde_json::to_vec(state)?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.state_key())
+            .body(body.into())
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Runs a single retention pass, resuming from the previously persisted marker if
+    /// today's sweep was interrupted. Returns the final [`RetentionState`].
+    pub async fn run_once(&self) -> Result<RetentionState> {
+        let today = Utc::now().date_naive();
+        let mut last_marker = match self.state().await? {
+            Some(RetentionState::Running { date, last_marker, .. }) if date == today => {
+                last_marker
+            }
+            _ => None,
+        };
+
+        let mut generations_scanned = 0u64;
+        let mut seen = HashSet::new();
+        let mut generations = Vec::new();
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(format!("{}-", self.db_name));
+            if let Some(marker) = &last_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            let objs = match response.contents() {
+                Some(objs) => objs,
+                None => break,
+            };
+            for obj in objs {
+                if let Some(key) = obj.key() {
+                    if let Some(uuid) = crate::s3::generation_from_key(key, &self.db_name) {
+                        if seen.insert(uuid) {
+                            generations.push((uuid, uuid_to_datetime(&uuid)));
+                            generations_scanned += 1;
+                            if generations_scanned % CHECKPOINT_EVERY == 0 {
+                                self.save_state(&RetentionState::Running {
+                                    date: today,
+                                    last_marker: last_marker.clone(),
+                                    generations_scanned,
+                                    generations_expired: 0,
+                                })
+                                .await?;
+                            }
+                        }
+                    }
+                }
+            }
+            if !response.is_truncated() {
+                break;
+            }
+            last_marker = response
+                .next_marker()
+                .map(|s| s.to_owned())
+                .or_else(|| objs.last().and_then(|obj| obj.key()).map(|s| s.to_owned()));
+            if last_marker.is_none() {
+                break;
+            }
+        }
+
+        generations.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut generations_expired = 0u64;
+        let mut kept_days = HashSet::new();
+        let mut kept_weeks = HashSet::new();
+        for (rank, (uuid, datetime)) in generations.iter().enumerate() {
+            if should_keep(&self.policy, rank as u64, today, *datetime, &mut kept_days, &mut kept_weeks) {
+                continue;
+            }
+            info!("Expiring generation {} ({})", uuid, datetime);
+            self.remove_generation(*uuid).await?;
+            generations_expired += 1;
+        }
+
+        let final_state = RetentionState::Completed { date: today };
+        self.save_state(&final_state).await?;
+        Ok(final_state)
+    }
+
+    async fn remove_generation(&self, generation: uuid::Uuid) -> Result<()> {
+        let mut next_marker = None;
+        loop {
+            let mut list_request = self
+                .client
+                .list_objects()
+                .bucket(&self.bucket)
+                .prefix(format!("{}-{}-", &self.db_name, generation));
+            if let Some(marker) = next_marker {
+                list_request = list_request.marker(marker);
+            }
+            let response = list_request.send().await?;
+            let objs = match response.contents() {
+                Some(objs) => objs,
+                None => return Ok(()),
+            };
+            for obj in objs {
+                if let Some(key) = obj.key() {
+                    self.client
+                        .delete_object()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .send()
+                        .await?;
+                }
+            }
+            next_marker = response.next_marker().map(|s| s.to_owned());
+            if next_marker.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs [`Self::run_once`] on a daily cadence until the process exits.
+    pub async fn run_forever(self) {
+        loop {
+            match self.run_once().await {
+                Ok(state) => info!("Retention sweep finished: {:?}", state),
+                Err(e) => warn!("Retention sweep failed: {}", e),
+            }
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn keep_newest_overrides_max_age() {
+        let policy = RetentionPolicy {
+            keep_newest: Some(1),
+            max_age: Some(Duration::days(1)),
+            ..Default::default()
+        };
+        let mut kept_days = HashSet::new();
+        let mut kept_weeks = HashSet::new();
+        let old = date(2020, 1, 1).and_hms_opt(0, 0, 0).unwrap();
+        assert!(should_keep(&policy, 0, date(2020, 6, 1), old, &mut kept_days, &mut kept_weeks));
+    }
+
+    #[test]
+    fn expires_past_max_age_with_no_exceptions() {
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::days(1)),
+            ..Default::default()
+        };
+        let mut kept_days = HashSet::new();
+        let mut kept_weeks = HashSet::new();
+        let old = date(2020, 1, 1).and_hms_opt(0, 0, 0).unwrap();
+        assert!(!should_keep(&policy, 5, date(2020, 6, 1), old, &mut kept_days, &mut kept_weeks));
+    }
+
+    #[test]
+    fn keeps_one_per_day_past_max_age() {
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::days(1)),
+            keep_one_per_day: true,
+            ..Default::default()
+        };
+        let mut kept_days = HashSet::new();
+        let mut kept_weeks = HashSet::new();
+        let today = date(2020, 6, 1);
+        let first = date(2020, 1, 1).and_hms_opt(10, 0, 0).unwrap();
+        let second = date(2020, 1, 1).and_hms_opt(2, 0, 0).unwrap();
+        assert!(should_keep(&policy, 1, today, first, &mut kept_days, &mut kept_weeks));
+        assert!(!should_keep(&policy, 2, today, second, &mut kept_days, &mut kept_weeks));
+    }
+}
@@ -0,0 +1,440 @@
+//! Safe `WalManager`/`Wal` wrappers that shadow every committed WAL frame into bottomless
+//! storage, replacing the hand-rolled `extern "C"` vtable this crate used to build by
+//! poking function pointers into a raw `libsql_wal_methods` struct. [`BottomlessWalManager`]
+//! wraps whatever [`WalManager`] sqlite would otherwise use and hands back a
+//! [`BottomlessWal`] from `open`, which forwards every call to the wrapped WAL and stages
+//! committed pages with the [`Replicator`](crate::s3::Replicator) driving this
+//! database's generation. Frames are buffered per-transaction and only flushed to
+//! bottomless storage on commit, so an `xUndo`/savepoint rollback in sqlite can discard
+//! staged pages before they ever reach remote storage. When the replicator's
+//! [`ReplicationMode`](crate::s3::ReplicationMode) wants it, a changeset [`Session`] is
+//! also attached and its accumulated changeset is shipped to a parallel logical
+//! replication channel alongside each physical commit. `bottomless_methods` in `lib.rs`
+//! is the only place left that deals with the raw C ABI -- it just builds one of these
+//! and hands it to libsql-sys to bridge into the vtable sqlite actually calls.
+
+use crate::metrics::{Metrics, METRICS_ADDR_ENV};
+use crate::s3::Replicator;
+use bytes::Bytes;
+use libsql_sys::session::Session;
+use libsql_sys::wal::{
+    BusyHandler, CheckpointCallback, CheckpointMode, PageHeaders, Sqlite3Db, Sqlite3File,
+    UndoHandler, Vfs, Wal, WalManager,
+};
+use std::ffi::CStr;
+use std::num::NonZeroU32;
+use std::os::raw::c_int;
+use std::sync::Arc;
+
+/// Attaches [`Metrics`] to `replicator` and starts serving them on
+/// [`METRICS_ADDR_ENV`], if that variable is set -- this is the only place a
+/// [`Replicator`] actually driving sqlite writes gets instrumented; without it every
+/// counter [`crate::s3::Replicator::commit`] records stays permanently empty. The scrape
+/// server runs on its own thread with its own tiny runtime, since `WalManager::open` is
+/// called from sqlite's C ABI with no async context to spawn onto.
+fn attach_metrics(replicator: Replicator) -> Replicator {
+    let addr = match std::env::var(METRICS_ADDR_ENV) {
+        Ok(addr) => addr,
+        Err(_) => return replicator,
+    };
+    let addr: std::net::SocketAddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            tracing::error!("Invalid {}: {}", METRICS_ADDR_ENV, e);
+            return replicator;
+        }
+    };
+
+    let metrics = Arc::new(Metrics::new());
+    let replicator = replicator.with_metrics(metrics.clone());
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                tracing::error!("Failed to start metrics server runtime: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = runtime.block_on(crate::metrics::serve(metrics, addr)) {
+            tracing::error!("Metrics server exited: {}", e);
+        }
+    });
+    replicator
+}
+
+/// [`WalManager`] that wraps the platform's default WAL implementation `T` and attaches a
+/// [`Replicator`] to every database it opens.
+#[derive(Clone)]
+pub struct BottomlessWalManager<T> {
+    underlying: T,
+}
+
+impl<T> BottomlessWalManager<T> {
+    pub fn new(underlying: T) -> Self {
+        Self { underlying }
+    }
+}
+
+impl<T: WalManager> WalManager for BottomlessWalManager<T> {
+    type Wal = BottomlessWal<T::Wal>;
+
+    fn use_shared_memory(&self) -> bool {
+        self.underlying.use_shared_memory()
+    }
+
+    fn open(
+        &self,
+        vfs: &mut Vfs,
+        db_file: &mut Sqlite3File,
+        no_shm_mode: c_int,
+        max_log_size: i64,
+        db_path: &CStr,
+    ) -> anyhow::Result<Self::Wal> {
+        tracing::trace!("Opening {}", db_path.to_string_lossy());
+
+        let db_path = db_path.to_str()?;
+        let db_name = db_path.strip_suffix("-wal").unwrap_or(db_path);
+
+        let mut replicator = Replicator::new(db_name)?;
+        replicator = attach_metrics(replicator);
+
+        let native_db_size = db_file.file_size()?;
+        tracing::warn!(
+            "Native file size: {} ({} pages)",
+            native_db_size,
+            native_db_size / crate::s3::Replicator::PAGE_SIZE as i64
+        );
+
+        if native_db_size == 0 {
+            tracing::info!("Restoring data from bottomless storage");
+            restore_from_bottomless(db_file, db_name)?;
+        }
+
+        tracing::warn!(
+            "Generation {} ({:?})",
+            replicator.generation(),
+            replicator.generation().get_timestamp()
+        );
+
+        let inner = self
+            .underlying
+            .open(vfs, db_file, no_shm_mode, max_log_size, db_path)?;
+
+        /* TODO:
+            1. -wal file present -> refuse to start, checkpoint first
+            2. Main database file not empty:
+                a. Create a generation timeuuid
+                b. Upload the database file to timeuuid/file
+                c. Start a new backup session
+        */
+
+        Ok(BottomlessWal {
+            inner,
+            replicator,
+            staged_frames: Vec::new(),
+            savepoint_marks: Vec::new(),
+            session: None,
+            db: None,
+        })
+    }
+
+    fn close(
+        &self,
+        wal: &mut Self::Wal,
+        db: &mut Sqlite3Db,
+        sync_flags: c_int,
+        scratch: Option<&mut [u8]>,
+    ) -> anyhow::Result<()> {
+        tracing::debug!("Closing wal");
+        self.underlying.close(&mut wal.inner, db, sync_flags, scratch)
+    }
+
+    fn destroy_log(&self, vfs: &mut Vfs, db_path: &CStr) -> anyhow::Result<()> {
+        self.underlying.destroy_log(vfs, db_path)
+    }
+
+    fn log_exists(&self, vfs: &mut Vfs, db_path: &CStr) -> anyhow::Result<bool> {
+        self.underlying.log_exists(vfs, db_path)
+    }
+
+    fn destroy(self) {
+        self.underlying.destroy()
+    }
+}
+
+/// Materializes a freshly-created, empty database file from the most recent generation in
+/// bottomless storage before sqlite ever writes to it: downloads the base snapshot and
+/// replays every WAL segment after it, writing each reconstructed page through the safe
+/// [`Sqlite3File`] handle sqlite opened for us. See [`s3::Replicator::restore`](crate::s3::Replicator::restore)
+/// for the page-batch/progress-reportable replay and the `size_after` check that catches a
+/// truncated upload.
+fn restore_from_bottomless(db_file: &mut Sqlite3File, db_name: &str) -> anyhow::Result<()> {
+    let replicator = crate::s3::Replicator::new(db_name)?;
+    let outcome = replicator.restore(|progress| {
+        tracing::debug!("Restored {} pages so far", progress.pages_restored);
+    })?;
+
+    for (pgno, page) in &outcome.pages {
+        let offset = (*pgno as i64 - 1) * crate::s3::Replicator::PAGE_SIZE as i64;
+        db_file
+            .write(page, offset)
+            .map_err(|e| anyhow::anyhow!("Failed to write restored page {}: {}", pgno, e))?;
+    }
+
+    let final_size = outcome.pages.len() as i64 * crate::s3::Replicator::PAGE_SIZE as i64;
+    db_file
+        .truncate(final_size)
+        .map_err(|e| anyhow::anyhow!("Failed to truncate restored database to {} bytes: {}", final_size, e))?;
+
+    tracing::info!(
+        "Restored {} pages ({} bytes) from bottomless storage",
+        outcome.pages.len(),
+        final_size
+    );
+    Ok(())
+}
+
+/// [`Wal`] that forwards every call to the wrapped WAL `T`, shadowing committed frames
+/// into bottomless storage along the way.
+pub struct BottomlessWal<T> {
+    inner: T,
+    replicator: Replicator,
+    /// Frames staged since the last commit, not yet handed to the replicator. Flushed to
+    /// bottomless storage as one atomic batch on commit, so a transaction that gets
+    /// undone or rolled back to a savepoint never leaves a trace in remote storage.
+    staged_frames: Vec<(i64, Bytes)>,
+    /// Watermark into `staged_frames` recorded by each open savepoint, in nesting order,
+    /// alongside the WAL state `self.inner.savepoint` wrote into `rollback_data` for it.
+    /// The WAL state is kept so `savepoint_undo` can find which savepoint sqlite is
+    /// rolling back to (it hands back the same buffer `savepoint` filled in) instead of
+    /// assuming it's always the innermost one.
+    savepoint_marks: Vec<(Vec<u32>, usize)>,
+    /// Session tracking every table's changes for the logical replication channel, when
+    /// [`ReplicationMode::wants_logical`](crate::s3::ReplicationMode::wants_logical).
+    /// Attached lazily from [`Self::set_db`], the first point sqlite hands us a db handle,
+    /// and re-attached after every commit -- a session reports every change since it was
+    /// attached, so reusing one across commits would re-ship rows already shipped by an
+    /// earlier commit instead of just the ones since the last one.
+    session: Option<Session>,
+    /// Cached handle from the most recent [`Wal::set_db`] call, used to re-attach
+    /// [`Self::session`] once `insert_frames` has drained the old one.
+    db: Option<Sqlite3Db>,
+}
+
+impl<T> BottomlessWal<T> {
+    /// Attaches a fresh changeset session when the logical replication channel wants one
+    /// and none is currently attached. Called from [`Wal::set_db`] and again from
+    /// [`Wal::insert_frames`] right after a commit drains [`Self::session`], so the next
+    /// commit starts tracking changes from a clean slate.
+    fn attach_session(&mut self, db: &mut Sqlite3Db) {
+        if self.session.is_none() && self.replicator.replication_mode().wants_logical() {
+            match Session::create(db).and_then(|mut session| {
+                session.attach(None)?;
+                Ok(session)
+            }) {
+                Ok(session) => self.session = Some(session),
+                Err(e) => tracing::error!("Failed to attach changeset session: {}", e),
+            }
+        }
+    }
+}
+
+impl<T: Wal> Wal for BottomlessWal<T> {
+    fn limit(&mut self, size: i64) {
+        self.inner.limit(size)
+    }
+
+    fn begin_read_txn(&mut self) -> anyhow::Result<bool> {
+        self.inner.begin_read_txn()
+    }
+
+    fn end_read_txn(&mut self) {
+        self.inner.end_read_txn()
+    }
+
+    fn find_frame(&mut self, page_no: NonZeroU32) -> anyhow::Result<Option<NonZeroU32>> {
+        self.inner.find_frame(page_no)
+    }
+
+    fn read_frame(&mut self, frame_no: NonZeroU32, buffer: &mut [u8]) -> anyhow::Result<()> {
+        self.inner.read_frame(frame_no, buffer)
+    }
+
+    fn db_size(&self) -> u32 {
+        self.inner.db_size()
+    }
+
+    fn begin_write_txn(&mut self) -> anyhow::Result<()> {
+        self.inner.begin_write_txn()
+    }
+
+    fn end_write_txn(&mut self) -> anyhow::Result<()> {
+        self.inner.end_write_txn()
+    }
+
+    /// Clears every frame staged since the last commit -- the whole transaction is being
+    /// rolled back, so none of it may reach bottomless storage.
+    fn undo<U: UndoHandler>(&mut self, handler: Option<&mut U>) -> anyhow::Result<()> {
+        self.staged_frames.clear();
+        self.savepoint_marks.clear();
+        self.inner.undo(handler)
+    }
+
+    /// Records a watermark into `staged_frames` for this savepoint, so a later
+    /// [`Self::savepoint_undo`] knows how much staged data to discard. The WAL state
+    /// `self.inner.savepoint` writes into `rollback_data` is captured alongside it, since
+    /// that's the handle `savepoint_undo` gets back to identify which savepoint is being
+    /// rolled back to.
+    fn savepoint(&mut self, rollback_data: &mut [u32]) {
+        self.inner.savepoint(rollback_data);
+        self.savepoint_marks
+            .push((rollback_data.to_vec(), self.staged_frames.len()));
+    }
+
+    /// Discards every frame staged after the matching [`Self::savepoint`], so rolling
+    /// back to a savepoint can never leak pages from the undone portion of the
+    /// transaction into bottomless storage. `ROLLBACK TO` a savepoint that isn't the
+    /// innermost one implicitly releases every savepoint nested inside it too, so this
+    /// finds the target by matching the WAL state sqlite hands back against the one
+    /// captured by [`Self::savepoint`], then drops it and every mark nested after it --
+    /// not just the last one pushed, which would leave the released savepoints' stale
+    /// marks around for a later rollback to mis-truncate against.
+    fn savepoint_undo(&mut self, rollback_data: &mut [u32]) -> anyhow::Result<()> {
+        if let Some(index) = self
+            .savepoint_marks
+            .iter()
+            .rposition(|(data, _)| data.as_slice() == rollback_data)
+        {
+            let (_, mark) = self.savepoint_marks[index];
+            self.staged_frames.truncate(mark);
+            self.savepoint_marks.truncate(index);
+        }
+        self.inner.savepoint_undo(rollback_data)
+    }
+
+    /// Stages every page in `page_headers` without touching the replicator yet, and only
+    /// on `is_commit` flushes the surviving staged frames -- everything since the last
+    /// commit, minus whatever savepoint rollbacks discarded -- as one atomic batch. The
+    /// safe replacement for the old unconditional-`replicator.write` `xFrames` hook.
+    fn insert_frames(
+        &mut self,
+        page_size: c_int,
+        page_headers: &mut PageHeaders,
+        size_after: u32,
+        is_commit: bool,
+        sync_flags: c_int,
+    ) -> anyhow::Result<usize> {
+        for (pgno, data) in page_headers.iter() {
+            self.staged_frames.push((pgno, Bytes::copy_from_slice(data)));
+        }
+        if is_commit {
+            for (pgno, data) in self.staged_frames.drain(..) {
+                self.replicator.write(pgno * Replicator::PAGE_SIZE as i64, &data);
+            }
+            self.savepoint_marks.clear();
+            self.replicator.set_size_after(size_after);
+
+            if self.replicator.replication_mode().wants_logical() {
+                if let Some(mut session) = self.session.take() {
+                    match session.changeset() {
+                        Ok(changeset) => self.replicator.set_changeset(Bytes::from(changeset)),
+                        Err(e) => tracing::error!("Failed to capture session changeset: {}", e),
+                    }
+                    // The drained session only ever reports changes since it was
+                    // attached, so it's spent -- re-attach a fresh one now so the next
+                    // commit's changeset doesn't include rows already shipped by this one.
+                    if let Some(mut db) = self.db {
+                        self.attach_session(&mut db);
+                    }
+                }
+            }
+
+            if let Err(e) = self.replicator.commit() {
+                tracing::error!("Failed to replicate: {}", e);
+                return Err(e);
+            }
+        }
+        self.inner
+            .insert_frames(page_size, page_headers, size_after, is_commit, sync_flags)
+    }
+
+    /// Forwards to the wrapped WAL, then -- once `mode` asked for a `Restart`/`Truncate`
+    /// checkpoint and every frame in the local WAL got backfilled into the main database
+    /// file -- rolls bottomless storage over to a new generation: the replicator uploads
+    /// a compacted base snapshot of the now-checkpointed file and starts a fresh
+    /// generation timeuuid for subsequent `insert_frames` calls. This bounds a future
+    /// restore to the latest snapshot plus frames written since the rollover, instead of
+    /// an ever-growing log, and leaves the old generation free to be garbage-collected.
+    fn checkpoint(
+        &mut self,
+        db: &mut Sqlite3Db,
+        mode: CheckpointMode,
+        busy_handler: Option<&mut dyn BusyHandler>,
+        sync_flags: u32,
+        buf: &mut [u8],
+        checkpoint_cb: Option<&mut dyn CheckpointCallback>,
+        in_wal: Option<&mut i32>,
+        backfilled: Option<&mut i32>,
+    ) -> anyhow::Result<()> {
+        let mut frames_in_wal = 0i32;
+        let mut backfilled_frames = 0i32;
+        self.inner.checkpoint(
+            db,
+            mode,
+            busy_handler,
+            sync_flags,
+            buf,
+            checkpoint_cb,
+            Some(&mut frames_in_wal),
+            Some(&mut backfilled_frames),
+        )?;
+
+        if let Some(out) = in_wal {
+            *out = frames_in_wal;
+        }
+        if let Some(out) = backfilled {
+            *out = backfilled_frames;
+        }
+
+        let full_checkpoint = matches!(mode, CheckpointMode::Restart | CheckpointMode::Truncate)
+            && frames_in_wal > 0
+            && backfilled_frames == frames_in_wal;
+        if full_checkpoint {
+            tracing::info!(
+                "Checkpoint backfilled all {} frames, rolling over to a new generation",
+                frames_in_wal
+            );
+            if let Err(e) = self.replicator.rollover_generation() {
+                tracing::error!("Failed to roll over generation after checkpoint: {}", e);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn exclusive_mode(&mut self, op: c_int) -> anyhow::Result<()> {
+        self.inner.exclusive_mode(op)
+    }
+
+    fn uses_heap_memory(&self) -> bool {
+        self.inner.uses_heap_memory()
+    }
+
+    /// `set_db` is the first point sqlite hands us a db handle (`WalManager::open` never
+    /// gets one), so it is where we cache it and lazily attach the changeset session the
+    /// logical replication channel records every table's changes into.
+    fn set_db(&mut self, db: &mut Sqlite3Db) {
+        self.db = Some(*db);
+        self.attach_session(db);
+        self.inner.set_db(db)
+    }
+
+    fn callback(&self) -> i32 {
+        self.inner.callback()
+    }
+
+    fn frames_in_wal(&self) -> u32 {
+        self.inner.frames_in_wal()
+    }
+}